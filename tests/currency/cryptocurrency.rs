@@ -16,12 +16,16 @@ extern crate bodyparser;
 extern crate iron;
 extern crate router;
 extern crate serde;
+#[macro_use]
 extern crate serde_json;
 
-use exonum::blockchain::{ApiContext, Blockchain, Service, Transaction, TransactionService, ObserverService};
+use exonum::blockchain::{ApiContext, Block, Blockchain, Schema as BlockchainSchema, Service,
+                          ServiceContext, Transaction, TransactionService, ObserverService};
 use exonum::node::ApiSender;
 use exonum::messages::{Message, RawTransaction};
-use exonum::storage::{Fork, MapIndex, Snapshot};
+use exonum::storage::{
+    Fork, ListIndex, MapIndex, MapProof, ProofListIndex, ProofMapIndex, Snapshot,
+};
 use exonum::crypto::{Hash, PublicKey};
 use exonum::encoding;
 use exonum::encoding::serialize::FromHex;
@@ -37,10 +41,27 @@ use self::router::Router;
 const SERVICE_ID: u16 = 1;
 const TX_CREATE_WALLET_ID: u16 = 1;
 const TX_TRANSFER_ID: u16 = 2;
+const TX_REQUEST_PAYMENT_ID: u16 = 3;
+const TX_FULFILL_INVOICE_ID: u16 = 4;
+const TX_FAUCET_WITHDRAW_ID: u16 = 5;
 
 /// Initial balance of newly created wallet.
 pub const INIT_BALANCE: u64 = 100;
 
+/// Maximum amount, in the same smallest currency unit as `Wallet::balance`,
+/// a single wallet may withdraw from the faucet within one
+/// `FAUCET_WITHDRAWAL_WINDOW`.
+pub const FAUCET_WITHDRAWAL_LIMIT: u64 = 1_000;
+
+/// Length, in blocks, of the sliding window `FAUCET_WITHDRAWAL_LIMIT` is
+/// enforced over.
+pub const FAUCET_WITHDRAWAL_WINDOW: u64 = 3;
+
+/// Sentinel `TransferProof::height` written by `TxTransfer::execute`,
+/// marking a proof still awaiting the real post-commit height/state_hash
+/// filled in by `CurrencyService::handle_commit`.
+const PENDING_PROOF_HEIGHT: u64 = u64::max_value();
+
 // // // // // // // // // // PERSISTENT DATA // // // // // // // // // //
 
 encoding_struct! {
@@ -48,18 +69,60 @@ encoding_struct! {
         pub_key: &PublicKey,
         name: &str,
         balance: u64,
+        history_len: u64,
+        history_hash: &Hash,
     }
 }
 
 impl Wallet {
-    pub fn increase(self, amount: u64) -> Self {
+    pub fn increase(self, amount: u64, history_hash: &Hash) -> Self {
         let balance = self.balance() + amount;
-        Self::new(self.pub_key(), self.name(), balance)
+        let history_len = self.history_len() + 1;
+        Self::new(self.pub_key(), self.name(), balance, history_len, history_hash)
     }
 
-    pub fn decrease(self, amount: u64) -> Self {
+    pub fn decrease(self, amount: u64, history_hash: &Hash) -> Self {
         let balance = self.balance() - amount;
-        Self::new(self.pub_key(), self.name(), balance)
+        let history_len = self.history_len() + 1;
+        Self::new(self.pub_key(), self.name(), balance, history_len, history_hash)
+    }
+}
+
+/// A payment request raised by the payee, settled by a matching
+/// `TxFulfillInvoice` from the payer.
+encoding_struct! {
+    struct Invoice {
+        id: &Hash,
+        payee: &PublicKey,
+        amount: u64,
+        fulfilled: bool,
+    }
+}
+
+/// One withdrawal a wallet has made from the faucet, timestamped by the
+/// block height it was serviced at. `TxFaucetWithdraw` sums the `amount`
+/// of every record still within `FAUCET_WITHDRAWAL_WINDOW` blocks of the
+/// current height to enforce a true sliding window: individual
+/// withdrawals age out on their own instead of a fixed window resetting
+/// the whole counter at once.
+encoding_struct! {
+    struct FaucetWithdrawalRecord {
+        height: u64,
+        amount: u64,
+    }
+}
+
+/// A self-contained receipt binding a completed `TxTransfer` to the block
+/// it was committed in, so the payee can later prove receipt to a third
+/// party without that party having to replay the chain.
+encoding_struct! {
+    struct TransferProof {
+        tx_hash: &Hash,
+        from: &PublicKey,
+        to: &PublicKey,
+        amount: u64,
+        height: u64,
+        state_hash: &Hash,
     }
 }
 
@@ -74,19 +137,103 @@ impl<S: AsRef<Snapshot>> CurrencySchema<S> {
         CurrencySchema { view }
     }
 
-    pub fn wallets(&self) -> MapIndex<&Snapshot, PublicKey, Wallet> {
-        MapIndex::new("cryptocurrency.wallets", self.view.as_ref())
+    pub fn wallets(&self) -> ProofMapIndex<&Snapshot, PublicKey, Wallet> {
+        ProofMapIndex::new("cryptocurrency.wallets", self.view.as_ref())
     }
 
     /// Get a separate wallet from the storage.
     pub fn wallet(&self, pub_key: &PublicKey) -> Option<Wallet> {
         self.wallets().get(pub_key)
     }
+
+    /// Get a Merkle proof of presence (or absence) of a wallet under `pub_key`,
+    /// checkable against the root hash returned by `state_hash`.
+    pub fn wallet_proof(&self, pub_key: &PublicKey) -> MapProof<PublicKey, Wallet> {
+        self.wallets().get_proof(*pub_key)
+    }
+
+    /// Get the transaction history of a wallet: a hash for every
+    /// `TxCreateWallet`, `TxTransfer`, `TxFulfillInvoice` or
+    /// `TxFaucetWithdraw` that has changed it, in the order they were
+    /// applied.
+    pub fn wallet_history(&self, pub_key: &PublicKey) -> ProofListIndex<&Snapshot, Hash> {
+        ProofListIndex::new_in_family(
+            "cryptocurrency.wallet_history",
+            pub_key,
+            self.view.as_ref(),
+        )
+    }
+
+    pub fn invoices(&self) -> MapIndex<&Snapshot, Hash, Invoice> {
+        MapIndex::new("cryptocurrency.invoices", self.view.as_ref())
+    }
+
+    /// Get a single invoice by its id.
+    pub fn invoice(&self, id: &Hash) -> Option<Invoice> {
+        self.invoices().get(id)
+    }
+
+    /// A wallet's faucet withdrawal log, one record per serviced
+    /// `TxFaucetWithdraw`, in the order they were applied.
+    pub fn faucet_withdrawals(
+        &self,
+        pub_key: &PublicKey,
+    ) -> ListIndex<&Snapshot, FaucetWithdrawalRecord> {
+        ListIndex::new_in_family(
+            "cryptocurrency.faucet_withdrawals",
+            pub_key,
+            self.view.as_ref(),
+        )
+    }
+
+    /// Sum of everything a wallet has withdrawn from the faucet within
+    /// `FAUCET_WITHDRAWAL_WINDOW` blocks of `height`.
+    pub fn faucet_withdrawn_within_window(&self, pub_key: &PublicKey, height: u64) -> u64 {
+        self.faucet_withdrawals(pub_key)
+            .iter()
+            .filter(|record| height.saturating_sub(record.height()) < FAUCET_WITHDRAWAL_WINDOW)
+            .map(|record| record.amount())
+            .sum()
+    }
+
+    /// Current blockchain height, used to decide which faucet withdrawal
+    /// records are still inside the sliding window.
+    pub fn height(&self) -> u64 {
+        BlockchainSchema::new(self.view.as_ref()).height()
+    }
+
+    pub fn transfer_proofs(&self) -> MapIndex<&Snapshot, Hash, TransferProof> {
+        MapIndex::new("cryptocurrency.transfer_proofs", self.view.as_ref())
+    }
+
+    /// Get the payment-proof receipt issued for a completed transfer.
+    pub fn transfer_proof(&self, tx_hash: &Hash) -> Option<TransferProof> {
+        self.transfer_proofs().get(tx_hash)
+    }
 }
 
 impl<'a> CurrencySchema<&'a mut Fork> {
-    pub fn wallets_mut(&mut self) -> MapIndex<&mut Fork, PublicKey, Wallet> {
-        MapIndex::new("cryptocurrency.wallets", self.view)
+    pub fn wallets_mut(&mut self) -> ProofMapIndex<&mut Fork, PublicKey, Wallet> {
+        ProofMapIndex::new("cryptocurrency.wallets", self.view)
+    }
+
+    pub fn wallet_history_mut(&mut self, pub_key: &PublicKey) -> ProofListIndex<&mut Fork, Hash> {
+        ProofListIndex::new_in_family("cryptocurrency.wallet_history", pub_key, self.view)
+    }
+
+    pub fn invoices_mut(&mut self) -> MapIndex<&mut Fork, Hash, Invoice> {
+        MapIndex::new("cryptocurrency.invoices", self.view)
+    }
+
+    pub fn faucet_withdrawals_mut(
+        &mut self,
+        pub_key: &PublicKey,
+    ) -> ListIndex<&mut Fork, FaucetWithdrawalRecord> {
+        ListIndex::new_in_family("cryptocurrency.faucet_withdrawals", pub_key, self.view)
+    }
+
+    pub fn transfer_proofs_mut(&mut self) -> MapIndex<&mut Fork, Hash, TransferProof> {
+        MapIndex::new("cryptocurrency.transfer_proofs", self.view)
     }
 }
 
@@ -116,6 +263,44 @@ message! {
     }
 }
 
+/// Request a payment: published and signed by the payee to raise a
+/// pending invoice that anyone can later fulfill.
+message! {
+    struct TxRequestPayment {
+        const TYPE = SERVICE_ID;
+        const ID = TX_REQUEST_PAYMENT_ID;
+
+        payee: &PublicKey,
+        amount: u64,
+        seed: u64,
+    }
+}
+
+/// Fulfill a previously requested invoice.
+message! {
+    struct TxFulfillInvoice {
+        const TYPE = SERVICE_ID;
+        const ID = TX_FULFILL_INVOICE_ID;
+
+        payer: &PublicKey,
+        invoice_id: &Hash,
+        seed: u64,
+    }
+}
+
+/// Withdraw coins from the service faucet, up to `FAUCET_WITHDRAWAL_LIMIT`
+/// per `FAUCET_WITHDRAWAL_WINDOW`.
+message! {
+    struct TxFaucetWithdraw {
+        const TYPE = SERVICE_ID;
+        const ID = TX_FAUCET_WITHDRAW_ID;
+
+        recipient: &PublicKey,
+        amount: u64,
+        seed: u64,
+    }
+}
+
 // // // // // // // // // // CONTRACTS // // // // // // // // // //
 
 impl Transaction for TxCreateWallet {
@@ -129,7 +314,19 @@ impl Transaction for TxCreateWallet {
     fn execute(&self, view: &mut Fork) {
         let mut schema = CurrencySchema { view };
         if schema.wallet(self.pub_key()).is_none() {
-            let wallet = Wallet::new(self.pub_key(), self.name(), INIT_BALANCE);
+            let tx_hash = self.hash();
+            let mut history = schema.wallet_history_mut(self.pub_key());
+            history.push(tx_hash);
+            let history_hash = history.merkle_root();
+            let history_len = history.len();
+
+            let wallet = Wallet::new(
+                self.pub_key(),
+                self.name(),
+                INIT_BALANCE,
+                history_len,
+                &history_hash,
+            );
             schema.wallets_mut().put(self.pub_key(), wallet)
         }
     }
@@ -151,12 +348,137 @@ impl Transaction for TxTransfer {
         if let (Some(sender), Some(receiver)) = (sender, receiver) {
             let amount = self.amount();
             if sender.balance() >= amount {
-                let sender = sender.decrease(amount);
-                let receiver = receiver.increase(amount);
+                let tx_hash = self.hash();
+
+                let mut sender_history = schema.wallet_history_mut(self.from());
+                sender_history.push(tx_hash);
+                let sender_history_hash = sender_history.merkle_root();
+                let sender = sender.decrease(amount, &sender_history_hash);
+
+                let mut receiver_history = schema.wallet_history_mut(self.to());
+                receiver_history.push(tx_hash);
+                let receiver_history_hash = receiver_history.merkle_root();
+                let receiver = receiver.increase(amount, &receiver_history_hash);
+
                 let mut wallets = schema.wallets_mut();
                 wallets.put(self.from(), sender);
                 wallets.put(self.to(), receiver);
+
+                // The block this transfer lands in is not committed yet, so
+                // its real height/state_hash are not known here (and, for a
+                // block with several transfers, not even knowable here: a
+                // sibling transfer executing later in the same block would
+                // still change the wallets root). Record a pending proof and
+                // let `CurrencyService::handle_commit` fill in the real,
+                // final values once the whole block has been committed.
+                let proof = TransferProof::new(
+                    &tx_hash,
+                    self.from(),
+                    self.to(),
+                    amount,
+                    PENDING_PROOF_HEIGHT,
+                    &Hash::zero(),
+                );
+                schema.transfer_proofs_mut().put(&tx_hash, proof);
+            }
+        }
+    }
+}
+
+impl Transaction for TxRequestPayment {
+    /// Check correctness of the payee's signature.
+    fn verify(&self) -> bool {
+        self.verify_signature(self.payee())
+    }
+
+    /// Raise a pending invoice keyed by this transaction's own hash.
+    fn execute(&self, view: &mut Fork) {
+        let mut schema = CurrencySchema { view };
+        let id = self.hash();
+        if schema.invoice(&id).is_none() {
+            let invoice = Invoice::new(&id, self.payee(), self.amount(), false);
+            schema.invoices_mut().put(&id, invoice);
+        }
+    }
+}
+
+impl Transaction for TxFulfillInvoice {
+    /// Check correctness of the payer's signature.
+    fn verify(&self) -> bool {
+        self.verify_signature(self.payer())
+    }
+
+    /// Settle the invoice by moving its amount from the payer to the
+    /// payee, rejecting self-invoicing, double-fulfillment, and
+    /// insufficient balance.
+    fn execute(&self, view: &mut Fork) {
+        let mut schema = CurrencySchema { view };
+        let invoice = schema.invoice(self.invoice_id());
+        let payer = schema.wallet(self.payer());
+        if let (Some(invoice), Some(payer)) = (invoice, payer) {
+            if invoice.fulfilled() || *self.payer() == *invoice.payee() {
+                return;
+            }
+            let payee = schema.wallet(invoice.payee());
+            if let Some(payee) = payee {
+                let amount = invoice.amount();
+                if payer.balance() >= amount {
+                    let tx_hash = self.hash();
+
+                    let mut payer_history = schema.wallet_history_mut(self.payer());
+                    payer_history.push(tx_hash);
+                    let payer_history_hash = payer_history.merkle_root();
+                    let payer = payer.decrease(amount, &payer_history_hash);
+
+                    let mut payee_history = schema.wallet_history_mut(invoice.payee());
+                    payee_history.push(tx_hash);
+                    let payee_history_hash = payee_history.merkle_root();
+                    let payee = payee.increase(amount, &payee_history_hash);
+
+                    let mut wallets = schema.wallets_mut();
+                    wallets.put(self.payer(), payer);
+                    wallets.put(invoice.payee(), payee);
+
+                    let fulfilled = Invoice::new(invoice.id(), invoice.payee(), invoice.amount(), true);
+                    schema.invoices_mut().put(self.invoice_id(), fulfilled);
+                }
+            }
+        }
+    }
+}
+
+impl Transaction for TxFaucetWithdraw {
+    /// Check correctness of the recipient's signature.
+    fn verify(&self) -> bool {
+        self.verify_signature(self.recipient())
+    }
+
+    /// Mint `amount` into the recipient's wallet, rejecting the request if
+    /// it would push the wallet's withdrawals within the current
+    /// `FAUCET_WITHDRAWAL_WINDOW` above `FAUCET_WITHDRAWAL_LIMIT`.
+    fn execute(&self, view: &mut Fork) {
+        let mut schema = CurrencySchema { view };
+        let height = schema.height();
+        let recipient = schema.wallet(self.recipient());
+        if let Some(recipient) = recipient {
+            let withdrawn_so_far = schema.faucet_withdrawn_within_window(self.recipient(), height);
+
+            let amount = self.amount();
+            if withdrawn_so_far + amount > FAUCET_WITHDRAWAL_LIMIT {
+                return;
             }
+
+            let tx_hash = self.hash();
+            let mut history = schema.wallet_history_mut(self.recipient());
+            history.push(tx_hash);
+            let history_hash = history.merkle_root();
+            let recipient = recipient.increase(amount, &history_hash);
+            schema.wallets_mut().put(self.recipient(), recipient);
+
+            let record = FaucetWithdrawalRecord::new(height, amount);
+            schema
+                .faucet_withdrawals_mut(self.recipient())
+                .push(record);
         }
     }
 }
@@ -213,6 +535,166 @@ impl CryptocurrencyApi {
     fn get_wallets(&self, _: &mut Request) -> IronResult<Response> {
         self.ok_response(&serde_json::to_value(&self.wallets()).unwrap())
     }
+
+    /// Endpoint for retrieving a Merkle proof of a wallet's (non-)inclusion
+    /// in the latest committed state, together with the block header the
+    /// proof should be checked against.
+    fn get_wallet_proof(&self, req: &mut Request) -> IronResult<Response> {
+        use self::iron::modifiers::Header;
+
+        let path = req.url.path();
+        let wallet_key = path[path.len() - 2];
+        let public_key = PublicKey::from_hex(wallet_key).map_err(|e| {
+            IronError::new(ApiError::FromHex(e), (
+                Status::BadRequest,
+                Header(ContentType::json()),
+                "\"Invalid request param: `pub_key`\"",
+            ))
+        })?;
+
+        let view = self.blockchain.snapshot();
+        let schema = CurrencySchema::new(&view);
+        let proof = schema.wallet_proof(&public_key);
+        let block_header = self.blockchain.last_block();
+
+        self.ok_response(&serde_json::to_value(json!({
+            "block_header": block_header,
+            "proof": proof,
+        })).unwrap())
+    }
+
+    /// Endpoint for retrieving the ordered transaction history of a wallet.
+    fn get_wallet_history(&self, req: &mut Request) -> IronResult<Response> {
+        use self::iron::modifiers::Header;
+
+        let path = req.url.path();
+        let wallet_key = path[path.len() - 2];
+        let public_key = PublicKey::from_hex(wallet_key).map_err(|e| {
+            IronError::new(ApiError::FromHex(e), (
+                Status::BadRequest,
+                Header(ContentType::json()),
+                "\"Invalid request param: `pub_key`\"",
+            ))
+        })?;
+
+        let view = self.blockchain.snapshot();
+        let schema = CurrencySchema::new(&view);
+        let history: Vec<Hash> = schema.wallet_history(&public_key).iter().collect();
+        self.ok_response(&serde_json::to_value(&history).unwrap())
+    }
+
+    /// Endpoint exposing the service's configuration and current chain
+    /// state, so a client can bootstrap against it without hardcoding
+    /// `SERVICE_ID`/`INIT_BALANCE`.
+    fn get_info(&self, _: &mut Request) -> IronResult<Response> {
+        let view = self.blockchain.snapshot();
+        let schema = CurrencySchema::new(&view);
+
+        self.ok_response(&serde_json::to_value(json!({
+            "service_name": CurrencyService::NAME,
+            "service_id": CurrencyService::ID,
+            "init_balance": INIT_BALANCE,
+            "height": schema.height(),
+            "state_hash": schema.wallets().merkle_root(),
+            "wallets_count": schema.wallets().values().count(),
+        })).unwrap())
+    }
+
+    fn invoices(&self) -> Vec<Invoice> {
+        let view = self.blockchain.snapshot();
+        let schema = CurrencySchema::new(view);
+        schema.invoices().values().collect()
+    }
+
+    /// Endpoint for retrieving all invoices.
+    fn get_invoices(&self, _: &mut Request) -> IronResult<Response> {
+        self.ok_response(&serde_json::to_value(&self.invoices()).unwrap())
+    }
+
+    /// Endpoint for retrieving the payment-proof receipt of a completed
+    /// transfer.
+    fn get_transfer_proof(&self, req: &mut Request) -> IronResult<Response> {
+        use self::iron::modifiers::Header;
+
+        let path = req.url.path();
+        let tx_hash_hex = path[path.len() - 2];
+        let tx_hash = Hash::from_hex(tx_hash_hex).map_err(|e| {
+            IronError::new(ApiError::FromHex(e), (
+                Status::BadRequest,
+                Header(ContentType::json()),
+                "\"Invalid request param: `tx_hash`\"",
+            ))
+        })?;
+
+        let view = self.blockchain.snapshot();
+        let schema = CurrencySchema::new(&view);
+        if let Some(proof) = schema.transfer_proof(&tx_hash) {
+            self.ok_response(&serde_json::to_value(&proof).unwrap())
+        } else {
+            Err(IronError::new(ApiError::NotFound, (
+                Status::NotFound,
+                Header(ContentType::json()),
+                "\"Transfer proof not found\"",
+            )))
+        }
+    }
+
+    /// Endpoint for retrieving a single invoice by id.
+    fn get_invoice(&self, req: &mut Request) -> IronResult<Response> {
+        use self::iron::modifiers::Header;
+
+        let path = req.url.path();
+        let invoice_id = path.last().unwrap();
+        let id = Hash::from_hex(invoice_id).map_err(|e| {
+            IronError::new(ApiError::FromHex(e), (
+                Status::BadRequest,
+                Header(ContentType::json()),
+                "\"Invalid request param: `id`\"",
+            ))
+        })?;
+
+        let view = self.blockchain.snapshot();
+        let schema = CurrencySchema::new(&view);
+        if let Some(invoice) = schema.invoice(&id) {
+            self.ok_response(&serde_json::to_value(&invoice).unwrap())
+        } else {
+            Err(IronError::new(ApiError::NotFound, (
+                Status::NotFound,
+                Header(ContentType::json()),
+                "\"Invoice not found\"",
+            )))
+        }
+    }
+}
+
+/// Check that `proof` is a valid Merkle proof of `pub_key` mapping to
+/// `wallet` (or to nothing, if `wallet` is `None`) against `state_hash`.
+///
+/// Returns `false` both when the proof does not check out and when it
+/// checks out but claims a different value for `pub_key` than `wallet`,
+/// so a tampered balance is always rejected.
+pub fn verify_wallet_proof(
+    proof: &MapProof<PublicKey, Wallet>,
+    state_hash: Hash,
+    pub_key: &PublicKey,
+    wallet: Option<&Wallet>,
+) -> bool {
+    let checked = match proof.check() {
+        Ok(checked) => checked,
+        Err(_) => return false,
+    };
+    if checked.merkle_root() != state_hash {
+        return false;
+    }
+    checked.entries().get(pub_key) == wallet
+}
+
+/// Check a `TransferProof` against the block header it claims to have
+/// been committed in, without needing access to the rest of the chain.
+/// Rejects the proof if the claimed height or state hash does not match
+/// the block header exactly.
+pub fn verify_transfer_proof(proof: &TransferProof, block_header: &Block) -> bool {
+    block_header.height() == proof.height() && *block_header.state_hash() == *proof.state_hash()
 }
 
 impl Api for CryptocurrencyApi {
@@ -222,9 +704,39 @@ impl Api for CryptocurrencyApi {
         let get_wallets = move |req: &mut Request| self_.get_wallets(req);
         let self_ = self.clone();
         let get_wallet = move |req: &mut Request| self_.get_wallet(req);
+        let self_ = self.clone();
+        let get_wallet_proof = move |req: &mut Request| self_.get_wallet_proof(req);
+        let self_ = self.clone();
+        let get_wallet_history = move |req: &mut Request| self_.get_wallet_history(req);
+        let self_ = self.clone();
+        let get_invoices = move |req: &mut Request| self_.get_invoices(req);
+        let self_ = self.clone();
+        let get_invoice = move |req: &mut Request| self_.get_invoice(req);
+        let self_ = self.clone();
+        let get_info = move |req: &mut Request| self_.get_info(req);
+        let self_ = self.clone();
+        let get_transfer_proof = move |req: &mut Request| self_.get_transfer_proof(req);
 
         router.get("/v1/wallets", get_wallets, "get_wallets");
         router.get("/v1/wallet/:pub_key", get_wallet, "get_wallet");
+        router.get(
+            "/v1/wallet/:pub_key/proof",
+            get_wallet_proof,
+            "get_wallet_proof",
+        );
+        router.get(
+            "/v1/wallet/:pub_key/history",
+            get_wallet_history,
+            "get_wallet_history",
+        );
+        router.get("/v1/invoices", get_invoices, "get_invoices");
+        router.get("/v1/invoice/:id", get_invoice, "get_invoice");
+        router.get("/v1/info", get_info, "get_info");
+        router.get(
+            "/v1/transfer/:tx_hash/proof",
+            get_transfer_proof,
+            "get_transfer_proof",
+        );
     }
 }
 
@@ -235,7 +747,7 @@ pub struct CurrencyService;
 
 transaction_set! {
     CurrencyTransactions {
-        TxTransfer, TxCreateWallet
+        TxTransfer, TxCreateWallet, TxRequestPayment, TxFulfillInvoice, TxFaucetWithdraw
     }
 }
 
@@ -245,7 +757,8 @@ impl TransactionService for CurrencyService {
     type Transactions = CurrencyTransactions;
 
     fn state_hash(&self, snapshot: &Snapshot) -> Vec<Hash> {
-        Vec::new()
+        let schema = CurrencySchema::new(snapshot);
+        vec![schema.wallets().merkle_root()]
     }
 
     /// Create a REST `Handler` to process web requests to the node.
@@ -256,6 +769,42 @@ impl TransactionService for CurrencyService {
         };
         api.wire(router);
     }
+
+    /// Fill in the real, final height and state hash of every transfer
+    /// proof written by a transaction in the block that just got
+    /// committed. This has to happen here rather than in `execute`: the
+    /// committed `state_hash` is only known once the whole block (every
+    /// sibling transaction included) has been applied.
+    fn handle_commit(&self, context: &mut ServiceContext) {
+        let (height, state_hash) = {
+            let block = BlockchainSchema::new(context.fork().as_ref()).last_block();
+            (block.height(), *block.state_hash())
+        };
+
+        let pending: Vec<Hash> = {
+            let schema = CurrencySchema::new(context.fork().as_ref());
+            schema
+                .transfer_proofs()
+                .iter()
+                .filter(|(_, proof)| proof.height() == PENDING_PROOF_HEIGHT)
+                .map(|(tx_hash, _)| tx_hash)
+                .collect()
+        };
+
+        let mut schema = CurrencySchema { view: context.fork() };
+        for tx_hash in pending {
+            let proof = schema.transfer_proof(&tx_hash).unwrap();
+            let finalized = TransferProof::new(
+                proof.tx_hash(),
+                proof.from(),
+                proof.to(),
+                proof.amount(),
+                height,
+                &state_hash,
+            );
+            schema.transfer_proofs_mut().put(&tx_hash, finalized);
+        }
+    }
 }
 
 pub struct WalletsService;
@@ -292,3 +841,337 @@ impl ObserverService for WalletsService {
         api.wire(router);
     }
 }
+
+// // // // // // // // // // TESTS // // // // // // // // // //
+
+#[cfg(test)]
+mod tests {
+    use exonum::crypto;
+    use exonum::messages::Message;
+    use exonum_testkit::TestKitBuilder;
+
+    use super::{
+        CurrencySchema, CurrencyService, TxCreateWallet, TxFaucetWithdraw, TxFulfillInvoice,
+        TxRequestPayment, TxTransfer, FAUCET_WITHDRAWAL_LIMIT, FAUCET_WITHDRAWAL_WINDOW,
+        INIT_BALANCE, verify_transfer_proof, verify_wallet_proof,
+    };
+
+    #[test]
+    fn wallet_proof_checks_out_against_state_hash() {
+        let mut testkit = TestKitBuilder::validator()
+            .with_service(CurrencyService)
+            .create();
+
+        let (pub_key, key) = crypto::gen_keypair();
+        testkit.create_block_with_transaction(TxCreateWallet::new(&pub_key, "Alice", &key));
+
+        let snapshot = testkit.snapshot();
+        let schema = CurrencySchema::new(&snapshot);
+        let wallet = schema.wallet(&pub_key).unwrap();
+        let proof = schema.wallet_proof(&pub_key);
+        let state_hash = schema.wallets().merkle_root();
+
+        assert!(verify_wallet_proof(&proof, state_hash, &pub_key, Some(&wallet)));
+    }
+
+    #[test]
+    fn tampered_balance_fails_proof_verification() {
+        let mut testkit = TestKitBuilder::validator()
+            .with_service(CurrencyService)
+            .create();
+
+        let (pub_key, key) = crypto::gen_keypair();
+        testkit.create_block_with_transaction(TxCreateWallet::new(&pub_key, "Alice", &key));
+
+        let snapshot = testkit.snapshot();
+        let schema = CurrencySchema::new(&snapshot);
+        let wallet = schema.wallet(&pub_key).unwrap();
+        let proof = schema.wallet_proof(&pub_key);
+        let state_hash = schema.wallets().merkle_root();
+
+        let history_hash = schema.wallet_history(&pub_key).merkle_root();
+        let tampered = wallet.increase(1_000_000, &history_hash);
+        assert!(!verify_wallet_proof(&proof, state_hash, &pub_key, Some(&tampered)));
+    }
+
+    #[test]
+    fn wallet_history_records_every_transaction() {
+        let mut testkit = TestKitBuilder::validator()
+            .with_service(CurrencyService)
+            .create();
+
+        let (alice_pk, alice_key) = crypto::gen_keypair();
+        let (bob_pk, bob_key) = crypto::gen_keypair();
+        let create_alice = TxCreateWallet::new(&alice_pk, "Alice", &alice_key);
+        let create_bob = TxCreateWallet::new(&bob_pk, "Bob", &bob_key);
+        testkit.create_block_with_transactions(vec![create_alice.clone(), create_bob.clone()]);
+
+        let transfer = TxTransfer::new(&alice_pk, &bob_pk, 10, 0, &alice_key);
+        testkit.create_block_with_transaction(transfer.clone());
+
+        let snapshot = testkit.snapshot();
+        let schema = CurrencySchema::new(&snapshot);
+
+        let alice_history: Vec<_> = schema.wallet_history(&alice_pk).iter().collect();
+        assert_eq!(alice_history, vec![create_alice.hash(), transfer.hash()]);
+
+        let alice = schema.wallet(&alice_pk).unwrap();
+        assert_eq!(alice.history_len(), 2);
+        let expected_history_hash = schema.wallet_history(&alice_pk).merkle_root();
+        assert_eq!(*alice.history_hash(), expected_history_hash);
+    }
+
+    #[test]
+    fn invoice_is_fulfilled_and_moves_the_balance() {
+        let mut testkit = TestKitBuilder::validator()
+            .with_service(CurrencyService)
+            .create();
+
+        let (alice_pk, alice_key) = crypto::gen_keypair();
+        let (bob_pk, bob_key) = crypto::gen_keypair();
+        testkit.create_block_with_transactions(vec![
+            TxCreateWallet::new(&alice_pk, "Alice", &alice_key),
+            TxCreateWallet::new(&bob_pk, "Bob", &bob_key),
+        ]);
+
+        let request = TxRequestPayment::new(&bob_pk, 10, 0, &bob_key);
+        testkit.create_block_with_transaction(request.clone());
+
+        let fulfill = TxFulfillInvoice::new(&alice_pk, &request.hash(), 0, &alice_key);
+        testkit.create_block_with_transaction(fulfill.clone());
+
+        let snapshot = testkit.snapshot();
+        let schema = CurrencySchema::new(&snapshot);
+        let invoice = schema.invoice(&request.hash()).unwrap();
+        assert!(invoice.fulfilled());
+        assert_eq!(schema.wallet(&alice_pk).unwrap().balance(), INIT_BALANCE - 10);
+        assert_eq!(schema.wallet(&bob_pk).unwrap().balance(), INIT_BALANCE + 10);
+    }
+
+    #[test]
+    fn invoice_cannot_be_fulfilled_twice() {
+        let mut testkit = TestKitBuilder::validator()
+            .with_service(CurrencyService)
+            .create();
+
+        let (alice_pk, alice_key) = crypto::gen_keypair();
+        let (bob_pk, bob_key) = crypto::gen_keypair();
+        let (carol_pk, carol_key) = crypto::gen_keypair();
+        testkit.create_block_with_transactions(vec![
+            TxCreateWallet::new(&alice_pk, "Alice", &alice_key),
+            TxCreateWallet::new(&bob_pk, "Bob", &bob_key),
+            TxCreateWallet::new(&carol_pk, "Carol", &carol_key),
+        ]);
+
+        let request = TxRequestPayment::new(&bob_pk, 10, 0, &bob_key);
+        testkit.create_block_with_transaction(request.clone());
+        testkit.create_block_with_transaction(TxFulfillInvoice::new(
+            &alice_pk,
+            &request.hash(),
+            0,
+            &alice_key,
+        ));
+        testkit.create_block_with_transaction(TxFulfillInvoice::new(
+            &carol_pk,
+            &request.hash(),
+            0,
+            &carol_key,
+        ));
+
+        let snapshot = testkit.snapshot();
+        let schema = CurrencySchema::new(&snapshot);
+        assert_eq!(schema.wallet(&bob_pk).unwrap().balance(), INIT_BALANCE + 10);
+        assert_eq!(schema.wallet(&carol_pk).unwrap().balance(), INIT_BALANCE);
+    }
+
+    #[test]
+    fn self_invoicing_is_rejected() {
+        let mut testkit = TestKitBuilder::validator()
+            .with_service(CurrencyService)
+            .create();
+
+        let (bob_pk, bob_key) = crypto::gen_keypair();
+        testkit.create_block_with_transaction(TxCreateWallet::new(&bob_pk, "Bob", &bob_key));
+
+        let request = TxRequestPayment::new(&bob_pk, 10, 0, &bob_key);
+        testkit.create_block_with_transaction(request.clone());
+        testkit.create_block_with_transaction(TxFulfillInvoice::new(
+            &bob_pk,
+            &request.hash(),
+            0,
+            &bob_key,
+        ));
+
+        let snapshot = testkit.snapshot();
+        let schema = CurrencySchema::new(&snapshot);
+        assert_eq!(schema.wallet(&bob_pk).unwrap().balance(), INIT_BALANCE);
+        assert!(!schema.invoice(&request.hash()).unwrap().fulfilled());
+    }
+
+    #[test]
+    fn faucet_withdrawal_is_capped_within_the_window() {
+        let mut testkit = TestKitBuilder::validator()
+            .with_service(CurrencyService)
+            .create();
+
+        let (bob_pk, bob_key) = crypto::gen_keypair();
+        testkit.create_block_with_transaction(TxCreateWallet::new(&bob_pk, "Bob", &bob_key));
+        testkit.create_block_with_transaction(TxFaucetWithdraw::new(
+            &bob_pk,
+            FAUCET_WITHDRAWAL_LIMIT,
+            0,
+            &bob_key,
+        ));
+
+        let snapshot = testkit.snapshot();
+        let schema = CurrencySchema::new(&snapshot);
+        assert_eq!(
+            schema.wallet(&bob_pk).unwrap().balance(),
+            INIT_BALANCE + FAUCET_WITHDRAWAL_LIMIT
+        );
+
+        testkit.create_block_with_transaction(TxFaucetWithdraw::new(&bob_pk, 1, 1, &bob_key));
+
+        let snapshot = testkit.snapshot();
+        let schema = CurrencySchema::new(&snapshot);
+        assert_eq!(
+            schema.wallet(&bob_pk).unwrap().balance(),
+            INIT_BALANCE + FAUCET_WITHDRAWAL_LIMIT
+        );
+    }
+
+    #[test]
+    fn faucet_withdrawal_limit_resets_after_the_window_elapses() {
+        let mut testkit = TestKitBuilder::validator()
+            .with_service(CurrencyService)
+            .create();
+
+        let (bob_pk, bob_key) = crypto::gen_keypair();
+        testkit.create_block_with_transaction(TxCreateWallet::new(&bob_pk, "Bob", &bob_key));
+        testkit.create_block_with_transaction(TxFaucetWithdraw::new(
+            &bob_pk,
+            FAUCET_WITHDRAWAL_LIMIT,
+            0,
+            &bob_key,
+        ));
+
+        for _ in 0..FAUCET_WITHDRAWAL_WINDOW {
+            testkit.create_block();
+        }
+
+        testkit.create_block_with_transaction(TxFaucetWithdraw::new(&bob_pk, 1, 1, &bob_key));
+
+        let snapshot = testkit.snapshot();
+        let schema = CurrencySchema::new(&snapshot);
+        assert_eq!(
+            schema.wallet(&bob_pk).unwrap().balance(),
+            INIT_BALANCE + FAUCET_WITHDRAWAL_LIMIT + 1
+        );
+    }
+
+    #[test]
+    fn faucet_withdrawal_sliding_window_rejects_double_limit_across_a_tumbling_boundary() {
+        let mut testkit = TestKitBuilder::validator()
+            .with_service(CurrencyService)
+            .create();
+
+        let (bob_pk, bob_key) = crypto::gen_keypair();
+        testkit.create_block_with_transaction(TxCreateWallet::new(&bob_pk, "Bob", &bob_key));
+
+        // Split the limit across two withdrawals a block apart, so neither
+        // one alone looks suspicious, but together they use up the limit.
+        testkit.create_block_with_transaction(TxFaucetWithdraw::new(
+            &bob_pk,
+            FAUCET_WITHDRAWAL_LIMIT - 1,
+            0,
+            &bob_key,
+        ));
+        testkit.create_block_with_transaction(TxFaucetWithdraw::new(&bob_pk, 1, 1, &bob_key));
+
+        // One more block ages the first withdrawal out of the window, but
+        // the second withdrawal is still inside it. A tumbling window would
+        // wrongly reset the whole counter here and let the full limit
+        // through again.
+        testkit.create_block();
+        testkit.create_block_with_transaction(TxFaucetWithdraw::new(
+            &bob_pk,
+            FAUCET_WITHDRAWAL_LIMIT,
+            2,
+            &bob_key,
+        ));
+
+        let snapshot = testkit.snapshot();
+        let schema = CurrencySchema::new(&snapshot);
+        assert_eq!(
+            schema.wallet(&bob_pk).unwrap().balance(),
+            INIT_BALANCE + FAUCET_WITHDRAWAL_LIMIT
+        );
+    }
+
+    #[test]
+    fn chain_info_reflects_the_current_state() {
+        let mut testkit = TestKitBuilder::validator()
+            .with_service(CurrencyService)
+            .create();
+
+        let (alice_pk, alice_key) = crypto::gen_keypair();
+        testkit.create_block_with_transaction(TxCreateWallet::new(&alice_pk, "Alice", &alice_key));
+
+        let snapshot = testkit.snapshot();
+        let schema = CurrencySchema::new(&snapshot);
+        assert_eq!(schema.height(), 1);
+        assert_eq!(schema.wallets().values().count(), 1);
+        assert_eq!(schema.wallets().merkle_root(), schema.wallet_proof(&alice_pk).check().unwrap().merkle_root());
+    }
+
+    #[test]
+    fn transfer_proof_checks_out_against_the_committing_block() {
+        let mut testkit = TestKitBuilder::validator()
+            .with_service(CurrencyService)
+            .create();
+
+        let (alice_pk, alice_key) = crypto::gen_keypair();
+        let (bob_pk, bob_key) = crypto::gen_keypair();
+        testkit.create_block_with_transactions(vec![
+            TxCreateWallet::new(&alice_pk, "Alice", &alice_key),
+            TxCreateWallet::new(&bob_pk, "Bob", &bob_key),
+        ]);
+
+        let transfer = TxTransfer::new(&alice_pk, &bob_pk, 10, 0, &alice_key);
+        testkit.create_block_with_transaction(transfer.clone());
+
+        let snapshot = testkit.snapshot();
+        let schema = CurrencySchema::new(&snapshot);
+        let proof = schema.transfer_proof(&transfer.hash()).unwrap();
+
+        let block_header = testkit.blockchain().last_block();
+        assert!(verify_transfer_proof(&proof, &block_header));
+    }
+
+    #[test]
+    fn transfer_proof_is_rejected_against_a_mismatched_block() {
+        let mut testkit = TestKitBuilder::validator()
+            .with_service(CurrencyService)
+            .create();
+
+        let (alice_pk, alice_key) = crypto::gen_keypair();
+        let (bob_pk, bob_key) = crypto::gen_keypair();
+        testkit.create_block_with_transactions(vec![
+            TxCreateWallet::new(&alice_pk, "Alice", &alice_key),
+            TxCreateWallet::new(&bob_pk, "Bob", &bob_key),
+        ]);
+
+        let transfer = TxTransfer::new(&alice_pk, &bob_pk, 10, 0, &alice_key);
+        testkit.create_block_with_transaction(transfer.clone());
+
+        let snapshot = testkit.snapshot();
+        let schema = CurrencySchema::new(&snapshot);
+        let proof = schema.transfer_proof(&transfer.hash()).unwrap();
+
+        // A later, unrelated block has a different height, so the same
+        // proof must no longer check out against it.
+        testkit.create_block();
+        let later_block_header = testkit.blockchain().last_block();
+        assert!(!verify_transfer_proof(&proof, &later_block_header));
+    }
+}